@@ -37,6 +37,83 @@ fn has_env_var_with_value(s: &str, v: &str) -> bool {
         .unwrap_or(false)
 }
 
+fn target_triple() -> String {
+    std::env::var("TARGET").expect("TARGET env var")
+}
+
+fn is_cross_compiling() -> bool {
+    let host = std::env::var("HOST").expect("HOST env var");
+    target_triple() != host
+}
+
+// LINK DYNAMICALLY INSTEAD OF THE FIXED STATIC BUILD
+fn is_dynamic_link() -> bool {
+    has_env_var_with_value("FFMPEG_LINK_DYNAMIC", "1")
+}
+
+// NEEDED ON i686 AND WHEN PRODUCING A SHARED ARTIFACT - 32-bit native compiles silently drop -fPIC otherwise
+fn needs_pic() -> bool {
+    is_dynamic_link() || target_triple().starts_with("i686") || target_triple().starts_with("i586") || target_triple().starts_with("i386")
+}
+
+// KEEP -O/-g IN SYNC BETWEEN FFMPEG'S OWN BUILD AND THE cbits COMPILE
+fn shared_codegen_flags() -> Vec<String> {
+    let mut flags = Vec::new();
+    if is_debug_mode() {
+        flags.push("-g".to_string());
+    }
+    for level in 0u8..=3 {
+        if opt_level_eq(level) {
+            flags.push(format!("-O{}", level));
+        }
+    }
+    flags
+}
+
+// FFmpeg's shared objects are versioned (libavcodec.so.58.134.100, avcodec-58.dll, ...) -
+// find a file under that naming so we can fail loudly if none exists.
+fn find_shared_object(lib_dir: &PathBuf, name: &str) -> Option<PathBuf> {
+    // NOT EXTRACTED/BUILT YET - NOTHING TO FIND
+    if !lib_dir.is_dir() {
+        return None;
+    }
+    let candidates = if cfg!(target_os = "macos") {
+        files_with_prefix(lib_dir, &format!("lib{}.", name))
+            .into_iter()
+            .filter(|path| path.file_name().and_then(|x| x.to_str()).unwrap_or("").contains(".dylib"))
+            .collect()
+    } else if cfg!(target_os = "windows") {
+        // MINGW/FFMPEG DLLS DROP THE "lib" PREFIX, E.G. "avcodec-58.dll"
+        files_with_prefix(lib_dir, &format!("{}-", name))
+            .into_iter()
+            .filter(|path| path.file_name().and_then(|x| x.to_str()).unwrap_or("").ends_with(".dll"))
+            .collect()
+    } else {
+        files_with_prefix(lib_dir, &format!("lib{}.so", name))
+    };
+    lookup_newest(candidates)
+}
+
+// WHERE THE extra/ STASH (config.h ETC) LIVES - VCPKG CRATE DOESN'T KNOW ABOUT THIS ONE
+#[cfg(target_os = "windows")]
+fn vcpkg_installed_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("VCPKG_INSTALLED_DIR") {
+        return PathBuf::from(dir);
+    }
+    let root = std::env::var("VCPKG_ROOT")
+        .expect("VCPKG_ROOT (or VCPKG_INSTALLED_DIR) must point at a vcpkg checkout with the ffmpeg port built for x64-windows-static");
+    PathBuf::from(root).join("installed").join("x64-windows-static")
+}
+
+// FINDS THE ffmpeg PORT VIA THE vcpkg CRATE INSTEAD OF HAND-ROLLING installed/<triplet> PATHS
+#[cfg(target_os = "windows")]
+fn vcpkg_ffmpeg() -> vcpkg::Library {
+    vcpkg::Config::new()
+        .target_triplet("x64-windows-static")
+        .find_package("ffmpeg")
+        .expect("vcpkg could not find the ffmpeg port - run `vcpkg install ffmpeg:x64-windows-static`, or point VCPKG_ROOT at the vcpkg checkout")
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // UTILS - BUILD
 ///////////////////////////////////////////////////////////////////////////////
@@ -136,37 +213,34 @@ fn cpy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) {
 // PATHS
 ///////////////////////////////////////////////////////////////////////////////
 
-pub const STATIC_LIBS: &[(&str, &str)] = &[
-    (
-        "avcodec",
-        "libavcodec/libavcodec.a",
-    ),
-    (
-        "avdevice",
-        "libavdevice/libavdevice.a",
-    ),
-    (
-        "avfilter",
-        "libavfilter/libavfilter.a",
-    ),
-    (
-        "avformat",
-        "libavformat/libavformat.a",
-    ),
-    (
-        "avutil",
-        "libavutil/libavutil.a",
-    ),
-    (
-        "swresample",
-        "libswresample/libswresample.a",
-    ),
-    (
-        "swscale",
-        "libswscale/libswscale.a",
-    ),
+// is_feature MARKS LIBS THAT ARE OPTIONAL VIA A CARGO FEATURE OF THE SAME NAME - avutil IS ALWAYS ON
+pub struct Library {
+    pub name: &'static str,
+    pub lib_path: &'static str,
+    pub is_feature: bool,
+}
+
+pub const STATIC_LIBS: &[Library] = &[
+    Library { name: "avcodec", lib_path: "libavcodec/libavcodec.a", is_feature: true },
+    Library { name: "avdevice", lib_path: "libavdevice/libavdevice.a", is_feature: true },
+    Library { name: "avfilter", lib_path: "libavfilter/libavfilter.a", is_feature: true },
+    Library { name: "avformat", lib_path: "libavformat/libavformat.a", is_feature: true },
+    Library { name: "avutil", lib_path: "libavutil/libavutil.a", is_feature: false },
+    Library { name: "swresample", lib_path: "libswresample/libswresample.a", is_feature: true },
+    Library { name: "swscale", lib_path: "libswscale/libswscale.a", is_feature: true },
 ];
 
+// A LIBRARY IS ENABLED IF IT ISN'T FEATURE-GATED, OR CARGO SET CARGO_FEATURE_<NAME> FOR IT
+fn library_enabled(lib: &Library) -> bool {
+    !lib.is_feature || std::env::var(format!("CARGO_FEATURE_{}", lib.name.to_uppercase())).is_ok()
+}
+
+fn header_library(header_path: &str) -> Option<&'static Library> {
+    STATIC_LIBS
+        .iter()
+        .find(|lib| header_path.starts_with(&format!("lib{}/", lib.name)))
+}
+
 pub const SEARCH_PATHS: &[&str] = &[
     "libavcodec",
     "libavdevice",
@@ -179,24 +253,242 @@ pub const SEARCH_PATHS: &[&str] = &[
     "libswscale",
 ];
 
+///////////////////////////////////////////////////////////////////////////////
+// SYSTEM FFMPEG (PKG-CONFIG)
+///////////////////////////////////////////////////////////////////////////////
+
+// WHERE BINDGEN LOOKS FOR HEADERS - OUR OWN VENDORED BUILD, OR AN INSTALLED FFMPEG FOUND VIA PKG-CONFIG
+enum HeaderRoot {
+    Vendored(PathBuf),
+    System(Vec<PathBuf>),
+}
+
+// MIRRORS ffmpeg-sys-next'S PKG-CONFIG PROBING - FALLS BACK TO THE VENDORED BUILD UNLESS EVERY LIB IS FOUND
+fn probe_system_ffmpeg() -> Option<Vec<PathBuf>> {
+    let forced = has_env_var_with_value("FFMPEG_SYS_USE_PKG_CONFIG", "1");
+    let mut include_paths: Vec<PathBuf> = Vec::new();
+    let pkg_names: Vec<String> = STATIC_LIBS
+        .iter()
+        .filter(|lib| library_enabled(lib))
+        .map(|lib| format!("lib{}", lib.name))
+        .collect();
+    // DON'T EMIT cargo:rustc-link-* UNTIL EVERY LIBRARY IS CONFIRMED PRESENT - OTHERWISE A PARTIAL
+    // PROBE LEAKS DIRECTIVES THAT CONFLICT WITH THE VENDORED FALLBACK BUILD
+    for pkg_name in &pkg_names {
+        match pkg_config::Config::new().cargo_metadata(false).probe(pkg_name) {
+            Ok(lib) => {
+                for path in lib.include_paths {
+                    if !include_paths.contains(&path) {
+                        include_paths.push(path);
+                    }
+                }
+            }
+            Err(err) => {
+                if forced {
+                    panic!("FFMPEG_SYS_USE_PKG_CONFIG is set but pkg-config couldn't find {}: {:?}", pkg_name, err);
+                }
+                // FALL BACK TO THE VENDORED BUILD
+                return None;
+            }
+        }
+    }
+    // EVERY LIBRARY IS CONFIRMED - NOW LET PKG-CONFIG ACTUALLY EMIT ITS DIRECTIVES
+    for pkg_name in &pkg_names {
+        pkg_config::Config::new().probe(pkg_name).expect("already probed above");
+    }
+    Some(include_paths)
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // CODEGEN
 ///////////////////////////////////////////////////////////////////////////////
 
-// See https://github.com/rust-lang/rust-bindgen/issues/687#issuecomment-450750547
+// IGNORES A FEW LIBC MACROS BINDGEN CAN'T PARSE AND TYPES AV_*/AVERROR MACROS PROPERLY
+// (see https://github.com/rust-lang/rust-bindgen/issues/687#issuecomment-450750547)
 #[derive(Debug, Clone)]
-struct IgnoreMacros(HashSet<String>);
+struct Callbacks {
+    ignored_macros: HashSet<String>,
+}
 
-impl bindgen::callbacks::ParseCallbacks for IgnoreMacros {
+impl bindgen::callbacks::ParseCallbacks for Callbacks {
     fn will_parse_macro(&self, name: &str) -> bindgen::callbacks::MacroParsingBehavior {
-        if self.0.contains(name) {
+        if self.ignored_macros.contains(name) {
             bindgen::callbacks::MacroParsingBehavior::Ignore
         } else {
             bindgen::callbacks::MacroParsingBehavior::Default
         }
     }
+
+    fn int_macro(&self, name: &str, _value: i64) -> Option<bindgen::callbacks::IntKind> {
+        if name == "AVERROR" || name.starts_with("AVERROR_") || name.starts_with("AV_ERROR_") {
+            // ERROR CODES ARE NEGATIVE - DON'T LET BINDGEN PICK AN UNSIGNED TYPE
+            Some(bindgen::callbacks::IntKind::Int)
+        } else if name.starts_with("AV_CH_") || name.starts_with("AV_CODEC_FLAG") || name.ends_with("_FLAGS") {
+            // BITMASKS - WIDE ENOUGH FOR THE CHANNEL-LAYOUT MASKS
+            Some(bindgen::callbacks::IntKind::ULongLong)
+        } else {
+            None
+        }
+    }
+
+    fn item_name(&self, original_item_name: &str) -> Option<String> {
+        for prefix in &["AVMEDIA_TYPE_", "AV_"] {
+            if let Some(stripped) = original_item_name.strip_prefix(prefix) {
+                if stripped.chars().next().map(|c| c.is_alphabetic()).unwrap_or(false) {
+                    return Some(stripped.to_string());
+                }
+            }
+        }
+        None
+    }
 }
 
+fn generate_bindings(header_root: &HeaderRoot, out_path: &PathBuf) {
+    println!("rerun-if-changed=headers");
+    let ffmpeg_headers = std::fs::read("headers").expect("unable to read headers file");
+    let ffmpeg_headers = String::from_utf8(ffmpeg_headers).expect("invalid utf8 file");
+    let ffmpeg_headers = ffmpeg_headers
+        .lines()
+        .collect::<Vec<&str>>();
+    assert!(
+        ffmpeg_headers
+            .iter()
+            .map(|x| x.trim())
+            .all(|x| !x.is_empty())
+    );
+
+    let gen_file_name = "bindings_ffmpeg.rs";
+    let callbacks = Callbacks {
+        ignored_macros: HashSet::from_iter(vec![
+            String::from("FP_INFINITE"),
+            String::from("FP_NAN"),
+            String::from("FP_NORMAL"),
+            String::from("FP_SUBNORMAL"),
+            String::from("FP_ZERO"),
+            String::from("IPPORT_RESERVED"),
+        ]),
+    };
+    let mut skip_codegen = out_path.join(gen_file_name).exists();
+    if has_env_var_with_value("FFDEV2", "2") {
+        skip_codegen = false;
+    }
+    // CONFIG
+    if !skip_codegen {
+        let codegen = bindgen::Builder::default();
+        let codegen = match header_root {
+            HeaderRoot::Vendored(source_path) => {
+                codegen.clang_arg(format!("-I{}", source_path.to_str().expect("PathBuf to str")))
+            }
+            HeaderRoot::System(include_paths) => {
+                include_paths.iter().fold(codegen, |codegen, path| {
+                    codegen.clang_arg(format!("-I{}", path.to_str().expect("PathBuf to str")))
+                })
+            }
+        };
+        let mut missing = Vec::new();
+        let codegen = ffmpeg_headers
+            .iter()
+            // DROP HEADERS THAT BELONG TO A LIBRARY DISABLED VIA ITS CARGO FEATURE
+            .filter(|path| header_library(path).map(|lib| library_enabled(lib)).unwrap_or(true))
+            .fold(codegen, |codegen: bindgen::Builder, path: &&str| -> bindgen::Builder {
+                let path: &str = path.clone();
+                let found = match header_root {
+                    HeaderRoot::Vendored(source_path) => {
+                        let full_path = source_path.join(path);
+                        if full_path.exists() { Some(full_path) } else { None }
+                    }
+                    HeaderRoot::System(include_paths) => {
+                        include_paths
+                            .iter()
+                            .map(|x| x.join(path))
+                            .find(|x| x.exists())
+                    }
+                };
+                match found {
+                    Some(full_path) => codegen.header(full_path.to_str().expect("PathBuf to str").to_string()),
+                    None => {
+                        missing.push(String::from(path));
+                        codegen
+                    }
+                }
+            });
+        #[cfg(target_os = "windows")]
+        let codegen = if let HeaderRoot::Vendored(source_path) = header_root {
+            vcpkg_ffmpeg().include_paths.iter().fold(
+                codegen.clang_arg(format!("-isystem{}", source_path.join("compat").join("atomics").join("win32").to_str().unwrap())),
+                |codegen, path| codegen.clang_arg(format!("-I{}", path.to_str().unwrap())),
+            )
+        } else {
+            codegen
+        };
+        if !missing.is_empty() {
+            panic!("missing headers: {:#?}", missing);
+        }
+        // RUN
+        codegen
+            .parse_callbacks(Box::new(callbacks.clone()))
+            .layout_tests(false)
+            .rustfmt_bindings(true)
+            .detect_include_paths(true)
+            .generate_comments(true)
+            .whitelist_function("av.*")
+            .whitelist_type("AV.*")
+            .generate()
+            .expect("Unable to generate bindings")
+            .write_to_file(out_path.join(gen_file_name))
+            .expect("Couldn't write bindings!");
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CROSS-COMPILATION
+///////////////////////////////////////////////////////////////////////////////
+
+// MAPS TARGET/HOST ONTO THE --cross-prefix/--arch/--target-os CONFIGURE WANTS - SET CROSS_COMPILE_PREFIX FOR ANYTHING EXOTIC
+fn cross_compile_configure_flags() -> Vec<String> {
+    if !is_cross_compiling() {
+        return Vec::new();
+    }
+    let target = target_triple();
+    let mut parts = target.splitn(3, '-');
+    let raw_arch = parts.next().unwrap_or("");
+    let rest = parts.next().unwrap_or("");
+    let os_and_abi = parts.next().unwrap_or("");
+    let target_os = if os_and_abi.contains("android") {
+        "android"
+    } else if os_and_abi.contains("linux") {
+        "linux"
+    } else if rest == "apple" {
+        "darwin"
+    } else if os_and_abi.contains("windows") {
+        "mingw32"
+    } else {
+        os_and_abi
+    };
+    let arch = match raw_arch {
+        "armv7" | "armv7a" => "arm",
+        "i686" => "x86",
+        other => other,
+    };
+    // e.g. "aarch64-unknown-linux-gnu" -> "aarch64-linux-gnu-"
+    let cross_prefix = std::env::var("CROSS_COMPILE_PREFIX")
+        .unwrap_or_else(|_| format!("{}-", target.replace("-unknown-", "-")));
+    let mut flags = vec![
+        "--enable-cross-compile".to_string(),
+        format!("--arch={}", arch),
+        format!("--target-os={}", target_os),
+        format!("--cross-prefix={}", cross_prefix),
+    ];
+    // MATCH cc-rs'S CONVENTION: - AND . AREN'T VALID IN SHELL ENV VAR NAMES, SO SWAP THEM FOR _
+    let env_target = target.replace(['-', '.'], "_");
+    if let Ok(cc) = std::env::var(format!("CC_{}", env_target)) {
+        flags.push(format!("--cc={}", cc));
+    }
+    if let Ok(sysroot) = std::env::var(format!("SYSROOT_{}", env_target)).or_else(|_| std::env::var("SYSROOT")) {
+        flags.push(format!("--sysroot={}", sysroot));
+    }
+    flags
+}
 
 ///////////////////////////////////////////////////////////////////////////////
 // BUILD PIPELINE
@@ -204,13 +496,39 @@ impl bindgen::callbacks::ParseCallbacks for IgnoreMacros {
 
 fn build() {
     let out_path = out_dir();
+    // PREFER A SYSTEM FFMPEG OVER BUILDING FROM VENDORED SOURCE
+    if let Some(include_paths) = probe_system_ffmpeg() {
+        generate_bindings(&HeaderRoot::System(include_paths.clone()), &out_path);
+        let mut cbits = cc::Build::new();
+        cbits.target(&target_triple());
+        if needs_pic() {
+            cbits.pic(true);
+        }
+        for flag in shared_codegen_flags() {
+            cbits.flag(&flag);
+        }
+        for path in &include_paths {
+            cbits.include(path.to_str().expect("PathBuf to str"));
+        }
+        cbits
+            .file("cbits/defs.c")
+            .file("cbits/img_utils.c")
+            .compile("cbits");
+        return;
+    }
     let source_path = out_path.join("FFmpeg-FFmpeg-2722fc2");
     // SPEED UP DEV - UNLESS IN RELASE MODE
     let already_built = {
         STATIC_LIBS
             .iter()
-            .map(|(_, x)| source_path.join(x))
-            .all(|x| x.exists())
+            .filter(|lib| library_enabled(lib))
+            .all(|lib| {
+                if is_dynamic_link() {
+                    find_shared_object(&source_path.join(format!("lib{}", lib.name)), lib.name).is_some()
+                } else {
+                    source_path.join(lib.lib_path).exists()
+                }
+            })
     };
     let mut skip_build = already_built && !is_release_mode();
     if has_env_var_with_value("FFDEV1", "1") {
@@ -259,13 +577,15 @@ fn build() {
             let result = child.wait_with_output().unwrap();
             assert!(result.status.success());
 
-            // copy needed files over 
+            // copy needed files over - vcpkg's ffmpeg port builds these for us,
+            // so grab them from its install tree rather than a dev machine path
             std::fs::copy(
-                "D:\\Robert\\repos\\vcpkg\\installed\\x64-windows-static\\include\\libavutil\\avconfig.h",
+                vcpkg_ffmpeg().include_paths[0].join("libavutil").join("avconfig.h"),
                 source_path.join("libavutil\\avconfig.h")
             ).unwrap();
+            // vcpkg's port doesn't install a public config.h - ours is hand-maintained in extra/
             std::fs::copy(
-                "D:\\Robert\\repos\\vcpkg\\installed\\x64-windows-static\\extra\\config.h",
+                vcpkg_installed_dir().join("extra").join("config.h"),
                 source_path.join("config.h")
             ).unwrap();
         }
@@ -275,28 +595,49 @@ fn build() {
     if skip_build == false {
         // CONFIGURE
         {
-            let mut configure_flags = vec![
-                "--disable-programs",
-                "--disable-doc",
-                "--disable-autodetect",
+            let mut configure_flags: Vec<String> = vec![
+                "--disable-programs".to_string(),
+                "--disable-doc".to_string(),
+                "--disable-autodetect".to_string(),
             ];
             // TRY TO SPEED THIS UP FOR DEV BUILDS
             if is_debug_mode() && opt_level_eq(0) {
-                configure_flags.push("--disable-optimizations");
-                configure_flags.push("--disable-debug");
-                configure_flags.push("--disable-stripping");
+                configure_flags.push("--disable-optimizations".to_string());
+                configure_flags.push("--disable-debug".to_string());
+                configure_flags.push("--disable-stripping".to_string());
+            }
+            // FFMPEG_LINK_DYNAMIC - BUILD .so/.dylib/.dll INSTEAD OF .a
+            if is_dynamic_link() {
+                configure_flags.push("--enable-shared".to_string());
+                configure_flags.push("--disable-static".to_string());
+            }
+            // FEATURES - ONLY BUILD THE LIBRARIES THE CARGO FEATURES ASK FOR
+            for lib in STATIC_LIBS.iter().filter(|lib| lib.is_feature) {
+                if library_enabled(lib) {
+                    configure_flags.push(format!("--enable-{}", lib.name));
+                } else {
+                    configure_flags.push(format!("--disable-{}", lib.name));
+                }
             }
-            let eval_configure = |flags: Vec<&str>| {
-                let flags = flags.join(" ");
-                std::process::Command::new("sh")
-                    .arg("-c")
-                    .arg(&format!(
-                        "cd {path} && ./configure {flags}",
-                        path=source_path.to_str().expect("PathBuf to str"),
-                        flags=flags,
-                    ))
+            // CROSS-COMPILE - TARGET != HOST, E.G. `cargo build --target=...`
+            configure_flags.extend(cross_compile_configure_flags());
+            // PIC - NEEDED ON i686 AND WHENEVER WE'RE PRODUCING A SHARED ARTIFACT
+            if needs_pic() {
+                configure_flags.push("--enable-pic".to_string());
+            }
+            // KEEP -O/-g IN SYNC WITH THE cbits cc::Build BELOW
+            let codegen_flags = shared_codegen_flags();
+            if !codegen_flags.is_empty() {
+                configure_flags.push(format!("--extra-cflags={}", codegen_flags.join(" ")));
+            }
+            // PASS FLAGS AS SEPARATE ARGS (NOT A SHELLED-OUT JOINED STRING) SO VALUES LIKE
+            // `--extra-cflags=-g -O0` DON'T GET WORD-SPLIT BY A SHELL
+            let eval_configure = |flags: Vec<String>| {
+                std::process::Command::new(source_path.join("configure"))
+                    .current_dir(&source_path)
+                    .args(&flags)
                     .output()
-                    .expect(&format!("ffmpeg configure script"))
+                    .expect("ffmpeg configure script")
             };
             let result = eval_configure(configure_flags.clone());
             if !result.status.success() {
@@ -308,7 +649,7 @@ fn build() {
                     .any(|x| x.contains("nasm/yasm not found or too old"));
                 // MAYBE RETRY (USE CRIPPLED BUILD)
                 if nasm_yasm_issue {
-                    configure_flags.push("--disable-x86asm");
+                    configure_flags.push("--disable-x86asm".to_string());
                     let result = eval_configure(configure_flags);
                     if !result.status.success() {
                         let stderr = String::from_utf8(result.stderr).expect("invalid str");
@@ -356,89 +697,50 @@ fn build() {
     }
     #[cfg(target_os = "windows")]
     {
-        println!("cargo:rustc-link-search=native={}", "D:\\Robert\\repos\\vcpkg\\installed\\x64-windows-static\\lib");
-        println!("cargo:rustc-link-search=static={}", "D:\\Robert\\repos\\vcpkg\\installed\\x64-windows-static\\lib");
-        
+        // vcpkg's x64-windows-static TRIPLET ONLY EVER PRODUCES STATIC LIBS - THE VENDORED
+        // SOURCE IS NEVER BUILT ON WINDOWS, SO THERE'S NO SHARED OBJECT TO LINK DYNAMICALLY
+        if is_dynamic_link() {
+            panic!("FFMPEG_LINK_DYNAMIC is not supported on Windows - vcpkg's x64-windows-static triplet only provides static libs");
+        }
+        // EMITS ITS OWN cargo:rustc-link-search/lib DIRECTIVES FOR THE PORT
+        let _ = vcpkg_ffmpeg();
+
+        // WINDOWS SDK IMPORT LIBS FFMPEG NEEDS - NOT PART OF THE vcpkg PORT, SO vcpkg CAN'T DISCOVER THEM
         println!("cargo:rustc-link-lib={}", "Bcrypt");
         println!("cargo:rustc-link-lib={}", "Secur32");
         println!("cargo:rustc-link-lib={}", "Ole32");
         println!("cargo:rustc-link-lib={}", "User32");
     }
     
-    for (name, _) in STATIC_LIBS {
-        println!("cargo:rustc-link-lib=static={}", name);
-    }
-    // CODEGEN
-    {
-        // SETUP
-        println!("rerun-if-changed=headers");
-        let ffmpeg_headers = std::fs::read("headers").expect("unable to read headers file");
-        let ffmpeg_headers = String::from_utf8(ffmpeg_headers).expect("invalid utf8 file");
-        let ffmpeg_headers = ffmpeg_headers
-            .lines()
-            .collect::<Vec<&str>>();
-        assert!(
-            ffmpeg_headers
-                .iter()
-                .map(|x| x.trim())
-                .all(|x| !x.is_empty())
-        );
-        
-        let gen_file_name = "bindings_ffmpeg.rs";
-        let ignored_macros = IgnoreMacros(HashSet::from_iter(vec![
-            String::from("FP_INFINITE"),
-            String::from("FP_NAN"),
-            String::from("FP_NORMAL"),
-            String::from("FP_SUBNORMAL"),
-            String::from("FP_ZERO"),
-            String::from("IPPORT_RESERVED"),
-        ]));
-        let mut skip_codegen = out_path.join(gen_file_name).exists();
-        if has_env_var_with_value("FFDEV2", "2") {
-            skip_codegen = false;
+    if is_dynamic_link() {
+        for lib in STATIC_LIBS.iter().filter(|lib| library_enabled(lib)) {
+            let lib_dir = source_path.join(format!("lib{}", lib.name));
+            let shared_object = find_shared_object(&lib_dir, lib.name)
+                .unwrap_or_else(|| panic!("FFMPEG_LINK_DYNAMIC is set but no shared object for lib{} was found in {:?} - did configure run with --enable-shared?", lib.name, lib_dir));
+            println!("cargo:rustc-link-search=native={}", lib_dir.to_str().expect("PathBuf to str"));
+            println!("cargo:rustc-link-lib=dylib={}", lib.name);
+            // MAKE THE RESULT FIND THE .so AT RUNTIME WITHOUT NEEDING LD_LIBRARY_PATH SET
+            #[cfg(not(target_os = "windows"))]
+            println!("cargo:rustc-link-arg=-Wl,-rpath,{}", lib_dir.to_str().expect("PathBuf to str"));
+            let _ = shared_object;
         }
-        // CONFIG
-        if !skip_codegen {
-            let codegen = bindgen::Builder::default();
-            let codegen = codegen.clang_arg(format!("-I{}", source_path.to_str().expect("PathBuf to str")));
-            let mut missing = Vec::new();
-            let codegen = ffmpeg_headers
-                .iter()
-                .fold(codegen, |codegen: bindgen::Builder, path: &&str| -> bindgen::Builder {
-                    let path: &str = path.clone();
-                    let path: PathBuf = source_path.join(path);
-                    let path: &str = path.to_str().expect("PathBuf to str");
-                    if !PathBuf::from(path).exists() {
-                        missing.push(String::from(path));
-                        codegen
-                    } else {
-                        codegen.header(path)
-                    }
-                });
-            #[cfg(target_os = "windows")]
-            let codegen = {
-                codegen.clang_arg(format!("-isystem{}", source_path.join("compat").join("atomics").join("win32").to_str().unwrap()))
-            };
-            if !missing.is_empty() {
-                panic!("missing headers: {:#?}", missing);
-            }
-            // RUN
-            codegen
-                .parse_callbacks(Box::new(ignored_macros.clone()))
-                .layout_tests(false)
-                .rustfmt_bindings(true)
-                .detect_include_paths(true)
-                .generate_comments(true)
-                .whitelist_function("av.*")
-                .whitelist_type("AV.*")
-                .generate()
-                .expect("Unable to generate bindings")
-                .write_to_file(out_path.join(gen_file_name))
-                .expect("Couldn't write bindings!");
+    } else {
+        for lib in STATIC_LIBS.iter().filter(|lib| library_enabled(lib)) {
+            println!("cargo:rustc-link-lib=static={}", lib.name);
         }
     }
+    // CODEGEN
+    generate_bindings(&HeaderRoot::Vendored(source_path.clone()), &out_path);
     // COMPILE CBITS
-    cc::Build::new()
+    let mut cbits = cc::Build::new();
+    cbits.target(&target_triple());
+    if needs_pic() {
+        cbits.pic(true);
+    }
+    for flag in shared_codegen_flags() {
+        cbits.flag(&flag);
+    }
+    cbits
         .include({
             source_path.to_str().expect("PathBuf to str")
         })
@@ -0,0 +1,3 @@
+#![allow(non_camel_case_types, non_snake_case, non_upper_case_globals)]
+
+include!(concat!(env!("OUT_DIR"), "/bindings_ffmpeg.rs"));